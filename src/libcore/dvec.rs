@@ -20,6 +20,7 @@ Note that recursive use is not permitted.
 */
 
 use cast;
+use iter;
 use prelude::*;
 use ptr::null;
 use vec;
@@ -98,24 +99,61 @@ priv impl<A> DVec<A> {
         }
     }
 
+    /**
+     * Swaps the current vector out and returns it, failing if the dvec
+     * is already checked out.  Unlike `check_out`, this does not
+     * arrange for the vector to ever be given back -- the caller is
+     * responsible for that (by handing it to something that will, like
+     * `DVecGuard` or `DVecItems`).
+     */
+    #[inline(always)]
+    fn take_or_fail(&self) -> ~[A] {
+        unsafe {
+            let mut data = cast::reinterpret_cast(&null::<()>());
+            data <-> self.data;
+            let data_ptr: *() = cast::reinterpret_cast(&data);
+            if data_ptr.is_null() { fail!(~"Recursive use of dvec"); }
+            data
+        }
+    }
+
     #[inline(always)]
     fn unwrap(self) -> ~[A] { unwrap(self) }
 }
 
+/**
+ * An RAII guard holding the `~[A]` that `check_out` swapped out of a
+ * `DVec`.  Its destructor gives the vector back to the dvec it came
+ * from unconditionally, whether the guard goes out of scope normally or
+ * during unwinding.  This is what makes a `DVec` recoverable after a
+ * caught failure in a `swap`/`borrow`/`pop`/`shift` closure, instead of
+ * leaving it poisoned in the "recursive use" state forever.
+ */
+struct DVecGuard<'self, A> {
+    dvec: &'self DVec<A>,
+    mut data: ~[A],
+}
+
+#[unsafe_destructor]
+impl<'self, A> Drop for DVecGuard<'self, A> {
+    fn finalize(&self) {
+        unsafe {
+            let mut data = ~[];
+            data <-> self.data;
+            self.dvec.give_back(data);
+        }
+    }
+}
+
 // In theory, most everything should work with any A, but in practice
 // almost nothing works without the copy bound due to limitations
 // around closures.
 pub impl<A> DVec<A> {
     // FIXME (#3758): This should not need to be public.
     #[inline(always)]
-    fn check_out<B>(f: &fn(v: ~[A]) -> B) -> B {
-        unsafe {
-            let mut data = cast::reinterpret_cast(&null::<()>());
-            data <-> self.data;
-            let data_ptr: *() = cast::reinterpret_cast(&data);
-            if data_ptr.is_null() { fail!(~"Recursive use of dvec"); }
-            return f(data);
-        }
+    fn check_out<B>(f: &fn(v: &mut ~[A]) -> B) -> B {
+        let mut guard = DVecGuard { dvec: self, data: self.take_or_fail() };
+        f(&mut guard.data)
     }
 
     /// Reserves space for N elements
@@ -130,7 +168,11 @@ pub impl<A> DVec<A> {
      */
     #[inline(always)]
     fn swap(&self, f: &fn(v: ~[A]) -> ~[A]) {
-        self.check_out(|v| self.give_back(f(v)))
+        do self.check_out |v| {
+            let mut data = ~[];
+            data <-> *v;
+            *v = f(data);
+        }
     }
 
     /// Returns the number of elements currently in the dvec
@@ -149,21 +191,13 @@ pub impl<A> DVec<A> {
 
     /// Remove and return the last element
     fn pop(&self) -> A {
-        do self.check_out |v| {
-            let mut v = v;
-            let result = v.pop();
-            self.give_back(v);
-            result
-        }
+        do self.check_out |v| { v.pop() }
     }
 
     /// Insert a single item at the front of the list
     fn unshift(&self, t: A) {
+        let data = self.take_or_fail();
         unsafe {
-            let mut data = cast::reinterpret_cast(&null::<()>());
-            data <-> self.data;
-            let data_ptr: *() = cast::reinterpret_cast(&data);
-            if data_ptr.is_null() { fail!(~"Recursive use of dvec"); }
             self.data = ~[t];
             self.data.push_all_move(data);
         }
@@ -178,40 +212,113 @@ pub impl<A> DVec<A> {
 
     /// Remove and return the first element
     fn shift(&self) -> A {
-        do self.check_out |v| {
-            let mut v = v;
-            let result = v.shift();
-            self.give_back(v);
-            result
-        }
+        do self.check_out |v| { v.shift() }
     }
 
     /// Reverse the elements in the list, in place
     fn reverse(&self) {
-        do self.check_out |v| {
-            let mut v = v;
-            vec::reverse(v);
-            self.give_back(v);
-        }
+        do self.check_out |v| { vec::reverse(*v) }
     }
 
     /// Gives access to the vector as a slice with immutable contents
     fn borrow<R>(&self, op: fn(x: &[A]) -> R) -> R {
-        do self.check_out |v| {
-            let result = op(v);
-            self.give_back(v);
-            result
-        }
+        do self.check_out |v| { op(*v) }
     }
 
     /// Gives access to the vector as a slice with mutable contents
     fn borrow_mut<R>(&self, op: &fn(x: &mut [A]) -> R) -> R {
+        do self.check_out |v| { op(*v) }
+    }
+
+    /**
+     * Returns a borrowing iterator over the elements of the dvec.
+     *
+     * As with `swap` or `borrow`, the dvec's contents are checked out
+     * for as long as the iterator is alive, so any attempt to use the
+     * dvec recursively while iterating will fail with "Recursive use of
+     * dvec".  The contents are given back when the iterator is
+     * dropped; exhausting it does not give them back by itself.
+     *
+     * This cannot be built on `check_out`, since `check_out` restores
+     * the dvec as soon as its closure returns, whereas the iterator
+     * needs to keep the contents checked out for as long as it lives.
+     */
+    fn iter(&self) -> DVecItems<A> {
+        DVecItems { dvec: self, data: self.take_or_fail(), idx: 0 }
+    }
+
+    /**
+     * Retains only the elements for which `f` returns `true`, in place.
+     *
+     * Built on `check_out`, so unlike `push_all`/`get` this moves
+     * elements instead of copying them and works for any `A`.  `f` is
+     * called once per element, front to back.  Elements are removed by
+     * shifting the remainder of the vector down in place, so the guard
+     * that `check_out` restores on a caught failure in `f` always holds
+     * the elements kept so far, compacted to the front, followed by the
+     * ones not yet passed to `f`, in their original order.
+     */
+    fn retain(&self, f: &fn(a: &A) -> bool) {
         do self.check_out |v| {
-            let mut v = v;
-            let result = op(v);
-            self.give_back(v);
-            result
-        }
+            let mut r = 0u;
+            while r < v.len() {
+                if f(&v[r]) {
+                    r += 1u;
+                } else {
+                    let mut i = r;
+                    while i + 1u < v.len() { v[i] <-> v[i + 1u]; i += 1u; }
+                    v.pop();
+                }
+            }
+        };
+    }
+
+    /**
+     * Removes and returns, as a new `DVec`, the elements for which `f`
+     * returns `true`, leaving the rest behind in order.
+     *
+     * See `retain` for the moving, front-to-back evaluation this is
+     * built on: a removed element is handed to the result dvec in the
+     * same step that closes the gap it left behind.  On a caught failure
+     * in `f`, the dvec keeps the elements not removed so far and those
+     * not yet tested; the elements already removed are dropped together
+     * with the unreturned result.
+     */
+    fn drain_filter(&self, f: &fn(a: &A) -> bool) -> DVec<A> {
+        let removed = DVec();
+        do self.check_out |v| {
+            let mut r = 0u;
+            while r < v.len() {
+                if f(&v[r]) {
+                    let mut i = r;
+                    while i + 1u < v.len() { v[i] <-> v[i + 1u]; i += 1u; }
+                    removed.push(v.pop());
+                } else {
+                    r += 1u;
+                }
+            }
+        };
+        removed
+    }
+
+    /**
+     * Moves every element through `f`, producing a new `DVec<B>`.
+     *
+     * See `retain` for why this works for any `A`.  Like `retain`, `f`
+     * is called front to back, and each transformed element is pushed
+     * straight into `result` as soon as it's produced.  On a caught
+     * failure in `f`, the dvec keeps the elements not yet transformed;
+     * the element being transformed and those already transformed are
+     * dropped together with the unreturned result.
+     */
+    fn map_in_place<B>(&self, f: &fn(a: A) -> B) -> DVec<B> {
+        let result = DVec();
+        do self.check_out |v| {
+            while !v.is_empty() {
+                result.push(f(v.shift()));
+            }
+        };
+        result
     }
 }
 
@@ -243,27 +350,26 @@ pub impl<A:Copy> DVec<A> {
     /**
      * Append all elements of an iterable.
      *
-     * Failure will occur if the iterable's `each()` method
-     * attempts to access this vector.
+     * Equivalent to `push_all()` but works with anything that
+     * implements `BaseIter`, such as the iterator returned by `iter()`
+     * and the `IterPipeline` adaptors built on it.
+     * Failure will occur if the iterable's `each()` method attempts to
+     * access this vector.
      */
-    /*
-    fn append_iter<A, I:iter::base_iter<A>>(ts: I) {
+    fn append_iter<I: iter::BaseIter<A>>(&self, ts: I) {
         do self.swap |v| {
-           let mut v = match ts.size_hint() {
-             none { v }
-             Some(h) {
-               let len = v.len() + h;
-               let mut v = v;
-               vec::reserve(v, len);
-               v
-            }
-           };
-
-        for ts.each |t| { v.push(*t) };
-           v
+            let mut v = match ts.size_hint() {
+                None => v,
+                Some(h) => {
+                    let mut v = v;
+                    vec::reserve(&mut v, v.len() + h);
+                    v
+                }
+            };
+            for ts.each |t| { v.push(*t); }
+            v
         }
     }
-    */
 
     /**
      * Gets a copy of the current contents.
@@ -272,11 +378,7 @@ pub impl<A:Copy> DVec<A> {
      */
     pure fn get(&self) -> ~[A] {
         unsafe {
-            do self.check_out |v| {
-                let w = copy v;
-                self.give_back(v);
-                w
-            }
+            do self.check_out |v| { copy *v }
         }
     }
 
@@ -346,6 +448,161 @@ pub impl<A:Copy> DVec<A> {
     }
 }
 
+/**
+ * A borrowing iterator over the elements of a `DVec`, created by
+ * `DVec::iter()`.
+ *
+ * While the iterator is alive the dvec's `~[A]` is checked out, exactly
+ * as it would be during `swap` or `borrow`, so recursive use of the
+ * dvec will fail with "Recursive use of dvec".  The contents are given
+ * back to the dvec when the iterator is dropped, not when it is
+ * exhausted.
+ */
+pub struct DVecItems<'self, A> {
+    priv dvec: &'self DVec<A>,
+    mut data: ~[A],
+    mut idx: uint,
+}
+
+impl<'self, A> iter::BaseIter<A> for DVecItems<'self, A> {
+    fn each(&self, blk: &fn(v: &A) -> bool) {
+        while self.idx < self.data.len() {
+            if !blk(&self.data[self.idx]) { return; }
+            self.idx += 1u;
+        }
+    }
+
+    fn size_hint(&self) -> Option<uint> {
+        Some(self.data.len() - self.idx)
+    }
+}
+
+#[unsafe_destructor]
+impl<'self, A> Drop for DVecItems<'self, A> {
+    fn finalize(&self) {
+        unsafe {
+            let mut data = ~[];
+            data <-> self.data;
+            self.dvec.give_back(data);
+        }
+    }
+}
+
+/// A lazy adaptor that yields only the elements of `inner` for which
+/// `pred` returns `true`.
+pub struct FilterIterator<'self, A, T> {
+    priv inner: T,
+    priv pred: &'self fn(v: &A) -> bool,
+}
+
+impl<'self, A, T: iter::BaseIter<A>> iter::BaseIter<A> for FilterIterator<'self, A, T> {
+    fn each(&self, blk: &fn(v: &A) -> bool) {
+        do self.inner.each |a| {
+            if (self.pred)(a) { blk(a) } else { true }
+        }
+    }
+
+    fn size_hint(&self) -> Option<uint> { None }
+}
+
+/// A lazy adaptor that transforms each element of `inner` through `f`.
+pub struct MapIterator<'self, A, B, T> {
+    priv inner: T,
+    priv f: &'self fn(v: &A) -> B,
+}
+
+impl<'self, A, B, T: iter::BaseIter<A>> iter::BaseIter<B> for MapIterator<'self, A, B, T> {
+    fn each(&self, blk: &fn(v: &B) -> bool) {
+        do self.inner.each |a| {
+            blk(&(self.f)(a))
+        }
+    }
+
+    fn size_hint(&self) -> Option<uint> { self.inner.size_hint() }
+}
+
+/// A lazy adaptor combining `filter` and `map`: elements for which `f`
+/// returns `None` are skipped, the rest are unwrapped.
+pub struct FilterMapIterator<'self, A, B, T> {
+    priv inner: T,
+    priv f: &'self fn(v: &A) -> Option<B>,
+}
+
+impl<'self, A, B, T: iter::BaseIter<A>> iter::BaseIter<B> for FilterMapIterator<'self, A, B, T> {
+    fn each(&self, blk: &fn(v: &B) -> bool) {
+        do self.inner.each |a| {
+            match (self.f)(a) {
+                Some(ref b) => blk(b),
+                None => true,
+            }
+        }
+    }
+
+    fn size_hint(&self) -> Option<uint> { None }
+}
+
+/// A lazy adaptor that yields the elements of `inner` up to (but not
+/// including) the first one for which `pred` returns `false`.
+pub struct TakeWhileIterator<'self, A, T> {
+    priv inner: T,
+    priv pred: &'self fn(v: &A) -> bool,
+}
+
+impl<'self, A, T: iter::BaseIter<A>> iter::BaseIter<A> for TakeWhileIterator<'self, A, T> {
+    fn each(&self, blk: &fn(v: &A) -> bool) {
+        do self.inner.each |a| {
+            (self.pred)(a) && blk(a)
+        }
+    }
+
+    fn size_hint(&self) -> Option<uint> { None }
+}
+
+/**
+ * Chainable `filter`/`map`/`filter_map`/`take_while` adaptors over
+ * anything implementing `BaseIter`, with a `collect()` terminal that
+ * pushes the results into a fresh `DVec` without ever materializing an
+ * intermediate `~[A]` between stages.
+ *
+ * As with the rest of `DVec`, almost nothing here works without the
+ * `Copy` bound due to limitations around closures, since `collect()`
+ * must copy each item out of the `&A`/`&B` the underlying `each()`
+ * hands it.
+ */
+pub trait IterPipeline<A> {
+    fn filter<'r>(self, pred: &'r fn(v: &A) -> bool) -> FilterIterator<'r, A, Self>;
+    fn map<'r, B>(self, f: &'r fn(v: &A) -> B) -> MapIterator<'r, A, B, Self>;
+    fn filter_map<'r, B>(self, f: &'r fn(v: &A) -> Option<B>)
+        -> FilterMapIterator<'r, A, B, Self>;
+    fn take_while<'r>(self, pred: &'r fn(v: &A) -> bool) -> TakeWhileIterator<'r, A, Self>;
+    fn collect(self) -> DVec<A>;
+}
+
+impl<A:Copy, T: iter::BaseIter<A>> IterPipeline<A> for T {
+    fn filter<'r>(self, pred: &'r fn(v: &A) -> bool) -> FilterIterator<'r, A, T> {
+        FilterIterator { inner: self, pred: pred }
+    }
+
+    fn map<'r, B>(self, f: &'r fn(v: &A) -> B) -> MapIterator<'r, A, B, T> {
+        MapIterator { inner: self, f: f }
+    }
+
+    fn filter_map<'r, B>(self, f: &'r fn(v: &A) -> Option<B>)
+        -> FilterMapIterator<'r, A, B, T> {
+        FilterMapIterator { inner: self, f: f }
+    }
+
+    fn take_while<'r>(self, pred: &'r fn(v: &A) -> bool) -> TakeWhileIterator<'r, A, T> {
+        TakeWhileIterator { inner: self, pred: pred }
+    }
+
+    fn collect(self) -> DVec<A> {
+        let result = DVec();
+        do self.each |a| { result.push(copy *a); true };
+        result
+    }
+}
+
 impl<A:Copy> Index<uint,A> for DVec<A> {
     #[inline(always)]
     pure fn index(&self, idx: uint) -> A {